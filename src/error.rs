@@ -0,0 +1,55 @@
+use thiserror::Error;
+
+/// Application-level errors, each carrying the HTTP status the worker should render it as.
+/// Keeping these distinct (rather than collapsing everything into `worker::Error::from(String)`)
+/// lets a bad request, a validation failure, and an origin outage produce different status codes.
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("invalid icon path: {0}")]
+    InvalidPath(String),
+
+    #[error("invalid icon dimensions: {0}")]
+    InvalidDimensions(String),
+
+    #[error("could not fetch source image: {0}")]
+    SourceFetch(String),
+
+    #[error("unsupported source image format: {0}")]
+    UnsupportedFormat(String),
+
+    #[error("could not decode source image: {0}")]
+    Decode(String),
+
+    #[error("could not encode icon image: {0}")]
+    Encode(String),
+
+    #[error("configuration error: {0}")]
+    Config(String),
+}
+
+impl AppError {
+    /// The HTTP status this error should be rendered as.
+    pub fn status(&self) -> u16 {
+        match self {
+            AppError::InvalidPath(_) => 400,
+            AppError::InvalidDimensions(_) => 403,
+            AppError::SourceFetch(_) => 502,
+            AppError::UnsupportedFormat(_) => 415,
+            AppError::Decode(_) => 422,
+            AppError::Encode(_) => 500,
+            AppError::Config(_) => 500,
+        }
+    }
+}
+
+impl From<worker::Error> for AppError {
+    fn from(e: worker::Error) -> Self {
+        AppError::SourceFetch(e.to_string())
+    }
+}
+
+impl From<image::ImageError> for AppError {
+    fn from(e: image::ImageError) -> Self {
+        AppError::Decode(e.to_string())
+    }
+}
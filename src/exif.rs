@@ -0,0 +1,32 @@
+use image::DynamicImage;
+use std::io::Cursor;
+
+/// Normalizes `image` to upright pixel data according to the EXIF `Orientation` tag found in
+/// the original source bytes, so later steps never need to think about camera rotation again.
+/// Missing, zero, or invalid tags are treated as orientation 1 (no-op).
+pub fn normalize_orientation(bytes: &[u8], image: DynamicImage) -> DynamicImage {
+    let orientation = read_orientation(bytes).unwrap_or(1);
+    apply_orientation(image, orientation)
+}
+
+fn read_orientation(bytes: &[u8]) -> Option<u32> {
+    let mut cursor = Cursor::new(bytes);
+    let exif = ::exif::Reader::new()
+        .read_from_container(&mut cursor)
+        .ok()?;
+    let field = exif.get_field(::exif::Tag::Orientation, ::exif::In::PRIMARY)?;
+    field.value.get_uint(0)
+}
+
+fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
@@ -0,0 +1,24 @@
+use image::DynamicImage;
+use worker::{Env, Error, Fetch, Method, Request, Result};
+
+/// Built-in icon served whenever the configured source image can't be fetched or decoded.
+const FALLBACK_ICON: &[u8] = include_bytes!("../assets/fallback-icon.png");
+
+/// Loads the fallback icon, preferring bytes fetched from `FALLBACK_ICON_URL` when it's set
+/// and reachable, and falling back to the icon embedded in the worker otherwise.
+pub async fn load(env: &Env) -> Result<DynamicImage> {
+    let bytes = match env.var("FALLBACK_ICON_URL") {
+        Ok(url) => fetch_bytes(&url.to_string())
+            .await
+            .unwrap_or_else(|_| FALLBACK_ICON.to_vec()),
+        Err(_) => FALLBACK_ICON.to_vec(),
+    };
+
+    image::load_from_memory(&bytes).map_err(|e| Error::from(e.to_string()))
+}
+
+async fn fetch_bytes(url: &str) -> Result<Vec<u8>> {
+    let req = Request::new(url, Method::Get)?;
+    let mut res = Fetch::Request(req).send().await?;
+    res.bytes().await
+}
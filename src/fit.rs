@@ -0,0 +1,151 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, Rgba};
+use worker::Url;
+
+/// How a non-square source image is fit into a square icon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitMode {
+    /// Center-crop to a square, then resize. The default for touch icons.
+    Cover,
+    /// Resize to fit within the square and pad the remainder with `background`.
+    Contain,
+    /// Resize both axes independently, ignoring aspect ratio.
+    Stretch,
+}
+
+impl FitMode {
+    /// Token used in the `fit` query parameter and cache-purge enumeration.
+    pub fn token(&self) -> &'static str {
+        match self {
+            FitMode::Cover => "cover",
+            FitMode::Contain => "contain",
+            FitMode::Stretch => "stretch",
+        }
+    }
+
+    fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "cover" => Some(FitMode::Cover),
+            "contain" => Some(FitMode::Contain),
+            "stretch" => Some(FitMode::Stretch),
+            _ => None,
+        }
+    }
+}
+
+/// Resolves the fit mode from a `?fit=` query hint, defaulting to `Cover`.
+pub fn resolve_fit_mode(url: &Url) -> FitMode {
+    url.query_pairs()
+        .find(|(k, _)| k == "fit")
+        .and_then(|(_, v)| FitMode::from_token(&v))
+        .unwrap_or(FitMode::Cover)
+}
+
+/// Parses the `?bg=rrggbb` or `?bg=rrggbbaa` query hint used by `Contain`, defaulting to
+/// transparent.
+pub fn resolve_background(url: &Url) -> Rgba<u8> {
+    url.query_pairs()
+        .find(|(k, _)| k == "bg")
+        .and_then(|(_, v)| parse_hex_color(&v))
+        .unwrap_or(Rgba([0, 0, 0, 0]))
+}
+
+fn parse_hex_color(value: &str) -> Option<Rgba<u8>> {
+    let value = value.trim_start_matches('#');
+    let bytes = match value.len() {
+        6 => [
+            u8::from_str_radix(&value[0..2], 16).ok()?,
+            u8::from_str_radix(&value[2..4], 16).ok()?,
+            u8::from_str_radix(&value[4..6], 16).ok()?,
+            255,
+        ],
+        8 => [
+            u8::from_str_radix(&value[0..2], 16).ok()?,
+            u8::from_str_radix(&value[2..4], 16).ok()?,
+            u8::from_str_radix(&value[4..6], 16).ok()?,
+            u8::from_str_radix(&value[6..8], 16).ok()?,
+        ],
+        _ => return None,
+    };
+
+    Some(Rgba(bytes))
+}
+
+/// Fits `source` into a `width`x`height` square according to `mode`.
+pub fn apply(
+    mode: FitMode,
+    source: &DynamicImage,
+    width: u32,
+    height: u32,
+    background: Rgba<u8>,
+) -> DynamicImage {
+    match mode {
+        FitMode::Cover => cover(source, width, height),
+        FitMode::Contain => contain(source, width, height, background),
+        FitMode::Stretch => source.resize_exact(width, height, FilterType::Triangle),
+    }
+}
+
+fn cover(source: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let (source_width, source_height) = source.dimensions();
+    let side = source_width.min(source_height);
+    let x = (source_width - side) / 2;
+    let y = (source_height - side) / 2;
+
+    source
+        .crop_imm(x, y, side, side)
+        .resize_exact(width, height, FilterType::Triangle)
+}
+
+fn contain(source: &DynamicImage, width: u32, height: u32, background: Rgba<u8>) -> DynamicImage {
+    let resized = source.resize(width, height, FilterType::Triangle);
+    let (resized_width, resized_height) = resized.dimensions();
+
+    let mut canvas = DynamicImage::ImageRgba8(image::ImageBuffer::from_pixel(
+        width, height, background,
+    ));
+
+    let x = ((width - resized_width) / 2) as i64;
+    let y = ((height - resized_height) / 2) as i64;
+    image::imageops::overlay(&mut canvas, &resized, x, y);
+
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wide_source() -> DynamicImage {
+        DynamicImage::ImageRgba8(image::ImageBuffer::from_pixel(100, 50, Rgba([255, 0, 0, 255])))
+    }
+
+    #[test]
+    fn cover_produces_exact_square() {
+        let icon = apply(FitMode::Cover, &wide_source(), 60, 60, Rgba([0, 0, 0, 0]));
+        assert_eq!(icon.dimensions(), (60, 60));
+    }
+
+    #[test]
+    fn contain_produces_exact_square_and_pads_with_background() {
+        let background = Rgba([10, 20, 30, 255]);
+        let icon = apply(FitMode::Contain, &wide_source(), 60, 60, background);
+        assert_eq!(icon.dimensions(), (60, 60));
+        // A wide source resized to fit a square leaves padding at the top, which must be the
+        // requested background color rather than source pixels.
+        assert_eq!(icon.get_pixel(0, 0), background);
+    }
+
+    #[test]
+    fn stretch_produces_exact_square_ignoring_aspect_ratio() {
+        let icon = apply(FitMode::Stretch, &wide_source(), 60, 60, Rgba([0, 0, 0, 0]));
+        assert_eq!(icon.dimensions(), (60, 60));
+    }
+
+    #[test]
+    fn parses_rgb_and_rgba_hex_colors() {
+        assert_eq!(parse_hex_color("ff0000"), Some(Rgba([255, 0, 0, 255])));
+        assert_eq!(parse_hex_color("#ff000080"), Some(Rgba([255, 0, 0, 128])));
+        assert_eq!(parse_hex_color("not-a-color"), None);
+    }
+}
@@ -0,0 +1,154 @@
+use worker::Url;
+
+use crate::error::AppError;
+
+/// Output image formats the worker knows how to encode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    Png,
+    WebP,
+    Avif,
+}
+
+impl ImageKind {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ImageKind::Png => "image/png",
+            ImageKind::WebP => "image/webp",
+            ImageKind::Avif => "image/avif",
+        }
+    }
+
+    /// Short token used in the `format` query parameter and cache keys.
+    pub fn token(&self) -> &'static str {
+        match self {
+            ImageKind::Png => "png",
+            ImageKind::WebP => "webp",
+            ImageKind::Avif => "avif",
+        }
+    }
+
+    fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "png" => Some(ImageKind::Png),
+            "webp" => Some(ImageKind::WebP),
+            "avif" => Some(ImageKind::Avif),
+            _ => None,
+        }
+    }
+
+    /// Negotiates a format from the `Accept` header. Deliberately stops at `WebP`: AVIF
+    /// encoding goes through the software `rav1e` encoder, whose CPU cost on this worker's
+    /// request budget hasn't been measured, so it's only ever chosen via an explicit
+    /// `?format=avif` opt-in rather than automatically for every AVIF-capable browser.
+    fn from_accept(accept: &str) -> Option<Self> {
+        let accept = accept.to_ascii_lowercase();
+        if accept.contains("image/webp") {
+            Some(ImageKind::WebP)
+        } else if accept.contains("image/png") {
+            Some(ImageKind::Png)
+        } else {
+            None
+        }
+    }
+
+    /// Parses the `DEFAULT_OUTPUT_FORMAT` env var into a supported format.
+    pub fn parse_default(value: &str) -> std::result::Result<Self, AppError> {
+        Self::from_token(value)
+            .ok_or_else(|| AppError::Config(format!("Unknown default image format: {}", value)))
+    }
+}
+
+/// Resolves the format to emit for a request: an explicit `?format=` query hint wins,
+/// then content negotiation via the `Accept` header, then the worker-wide default.
+pub fn resolve_format(url: &Url, accept: Option<&str>, default: ImageKind) -> ImageKind {
+    let from_query = url
+        .query_pairs()
+        .find(|(k, _)| k == "format")
+        .and_then(|(_, v)| ImageKind::from_token(&v));
+    if let Some(kind) = from_query {
+        return kind;
+    }
+
+    if let Some(kind) = accept.and_then(ImageKind::from_accept) {
+        return kind;
+    }
+
+    default
+}
+
+/// Builds the Cache API key for a resolved format at a given request URL. Both normal request
+/// handling and `/purge` key reconstruction go through this helper so the two can't drift. The
+/// query string is canonicalized (parameters sorted by key) first, so `?format=webp&fit=cover`
+/// and `?fit=cover&format=webp` — the same negotiated variant, sent in a different order —
+/// collapse onto the same key instead of silently caching (and purging) as two different ones.
+pub fn cache_key(url: &Url, format: ImageKind) -> String {
+    format!("{}::{}", canonicalize_query(url), format.token())
+}
+
+fn canonicalize_query(url: &Url) -> Url {
+    let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    pairs.sort();
+
+    let mut canonical = url.clone();
+    if pairs.is_empty() {
+        canonical.set_query(None);
+    } else {
+        // Re-encode through `query_pairs_mut` (rather than joining the decoded strings by
+        // hand) so a value containing a literal `&` or `=` can't be re-serialized into a
+        // string indistinguishable from a different set of params.
+        canonical.query_pairs_mut().clear().extend_pairs(&pairs);
+    }
+
+    canonical
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_key_is_independent_of_query_param_order() {
+        let a: Url = "https://example.com/apple-touch-icon.png?format=webp&fit=cover"
+            .parse()
+            .unwrap();
+        let b: Url = "https://example.com/apple-touch-icon.png?fit=cover&format=webp"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            cache_key(&a, ImageKind::WebP),
+            cache_key(&b, ImageKind::WebP)
+        );
+    }
+
+    #[test]
+    fn cache_key_still_distinguishes_different_values() {
+        let cover: Url = "https://example.com/apple-touch-icon.png?fit=cover"
+            .parse()
+            .unwrap();
+        let contain: Url = "https://example.com/apple-touch-icon.png?fit=contain"
+            .parse()
+            .unwrap();
+
+        assert_ne!(
+            cache_key(&cover, ImageKind::Png),
+            cache_key(&contain, ImageKind::Png)
+        );
+    }
+
+    #[test]
+    fn cache_key_does_not_collide_when_a_value_contains_query_delimiters() {
+        let one_param: Url = "https://example.com/apple-touch-icon.png?x=a%26y%3Db"
+            .parse()
+            .unwrap();
+        let two_params: Url = "https://example.com/apple-touch-icon.png?x=a&y=b"
+            .parse()
+            .unwrap();
+
+        assert_ne!(
+            cache_key(&one_param, ImageKind::Png),
+            cache_key(&two_params, ImageKind::Png)
+        );
+    }
+}
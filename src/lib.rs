@@ -3,23 +3,41 @@ use regex::Regex;
 use std::io::Cursor;
 use worker::*;
 
+mod error;
+mod exif;
+mod fallback;
+mod fit;
+mod format;
+mod purge;
 mod utils;
 
+use error::AppError;
+use fit::FitMode;
+use format::ImageKind;
+
 struct Icon {
     width: u32,
     height: u32,
 }
 
 impl Icon {
-    fn validate(&self) -> Result<()> {
+    fn validate(&self) -> std::result::Result<(), AppError> {
         if self.width < 1 || self.width > 500 {
-            return Err(Error::from("invalid width"));
+            return Err(AppError::InvalidDimensions(format!(
+                "invalid width: {}",
+                self.width
+            )));
         }
         if self.height < 1 || self.height > 500 {
-            return Err(Error::from("invalid height"));
+            return Err(AppError::InvalidDimensions(format!(
+                "invalid height: {}",
+                self.height
+            )));
         }
         if self.width != self.height {
-            return Err(Error::from("invalid size"));
+            return Err(AppError::InvalidDimensions(
+                "width and height must match".into(),
+            ));
         }
 
         Ok(())
@@ -43,17 +61,34 @@ pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Respo
     // Optionally, get more helpful error messages written to the console in the case of a panic.
     utils::set_panic_hook();
 
+    if req.method() == Method::Delete && req.path() == "/purge" {
+        return handle_purge(&req, &env).await;
+    }
+
     let icon = match parse_icon_path(&req.path().trim_start_matches("/")) {
         Ok(icon) => icon,
-        Err(e) => return Response::error(e.to_string(), 400),
+        Err(e) => return render_error(e),
     };
 
     if let Err(e) = icon.validate() {
-        return Response::error(e.to_string(), 403);
+        return render_error(e);
     }
 
+    let default_format = match env.var("DEFAULT_OUTPUT_FORMAT") {
+        Ok(v) => match ImageKind::parse_default(&v.to_string()) {
+            Ok(format) => format,
+            Err(e) => return render_error(e),
+        },
+        Err(_) => ImageKind::Png,
+    };
+    let accept = req.headers().get("accept")?;
+    let url = req.url()?;
+    let format = format::resolve_format(&url, accept.as_deref(), default_format);
+    let fit_mode = fit::resolve_fit_mode(&url);
+    let background = fit::resolve_background(&url);
+
     let cache = Cache::default();
-    let key = req.url()?.to_string();
+    let key = format::cache_key(&url, format);
     console_debug!("key = {}", key);
     let mut response;
     if let Some(resp) = cache.get(&key, true).await? {
@@ -62,71 +97,131 @@ pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Respo
     } else {
         console_debug!("Cache MISS!");
         let source_image_url = env.var("SOURCE_IMAGE_URL")?.to_string();
-        let source_image = fetch_source_image(&source_image_url).await?;
-        let icon_image = generate_icon(&icon, &source_image);
-        response = make_response(&icon_image)?;
-
-        response.headers_mut().set("cache-control", "s-maxage=10")?;
-        cache.put(key, response.cloned()?).await?;
+        let (source_image, is_fallback) = match fetch_source_image(&source_image_url).await {
+            Ok(img) => (img, false),
+            Err(_) => match fallback::load(&env).await {
+                Ok(img) => (img, true),
+                Err(e) => return Response::error(e.to_string(), 500),
+            },
+        };
+        let icon_image = generate_icon(&icon, &source_image, fit_mode, background);
+        response = match make_response(&icon_image, format) {
+            Ok(resp) => resp,
+            Err(e) => return render_error(e),
+        };
+
+        if is_fallback {
+            response.headers_mut().set("cache-control", "s-maxage=5")?;
+        } else {
+            response.headers_mut().set("cache-control", "s-maxage=10")?;
+            cache.put(key, response.cloned()?).await?;
+        }
     }
 
     Ok(response)
 }
 
-fn parse_icon_path(path: &str) -> Result<Icon> {
+/// Renders a structured `AppError` as the `Response` the worker sends to the client. This is
+/// the single point where an `AppError` becomes an HTTP status + message.
+fn render_error(e: AppError) -> Result<Response> {
+    Response::error(e.to_string(), e.status())
+}
+
+/// Evicts the cached icon variants after checking the shared-secret `x-purge-secret` header
+/// against the `PURGE_SECRET` env var, so arbitrary clients can't evict the cache.
+async fn handle_purge(req: &Request, env: &Env) -> Result<Response> {
+    let expected = env.var("PURGE_SECRET")?.to_string();
+    let provided = req.headers().get("x-purge-secret")?.unwrap_or_default();
+    if expected.is_empty() || !constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+        return Response::error("Forbidden", 403);
+    }
+
+    let purged = purge::purge_all(&req.url()?).await?;
+    Response::ok(format!("purged {} cached variant(s)", purged))
+}
+
+/// Compares two byte strings in time independent of where they first differ, so a timing
+/// attack can't be used to guess the purge shared secret one byte at a time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn parse_icon_path(path: &str) -> std::result::Result<Icon, AppError> {
     let re = Regex::new(r"^apple-touch-icon(-(\d+)x(\d+))?(-precomposed)?\.png").unwrap();
     let caps = re
-        .captures(&path)
-        .ok_or(format!("Unmached path: {}", path))?;
+        .captures(path)
+        .ok_or_else(|| AppError::InvalidPath(format!("Unmatched path: {}", path)))?;
 
     let width: u32 = caps.get(2).map_or("60", |m| m.as_str()).parse().unwrap();
     let height: u32 = caps.get(3).map_or("60", |m| m.as_str()).parse().unwrap();
     Ok(Icon { width, height })
 }
 
-async fn fetch_source_image(source_image_url: &str) -> Result<DynamicImage> {
+async fn fetch_source_image(source_image_url: &str) -> std::result::Result<DynamicImage, AppError> {
     let req = Request::new(source_image_url, Method::Get)?;
     let mut res = Fetch::Request(req).send().await?;
     let source = res.bytes().await?;
 
-    let content_type = res.headers().get("content-type")?;
-    let format = match content_type {
-        Some(t) => detect_image_format(t.as_str())?,
-        None => return Err(Error::from("Could not get content-type response header")),
-    };
+    let format = detect_image_format(&source)?;
 
-    let img = image::load_from_memory_with_format(&source, format)
-        .map_err(|e| Error::from(e.to_string()))?;
+    let img = image::load_from_memory_with_format(&source, format)?;
 
-    Ok(img)
+    Ok(exif::normalize_orientation(&source, img))
 }
 
-fn detect_image_format(content_type: &str) -> Result<ImageFormat> {
-    let format = match content_type {
-        "image/jpeg" => ImageFormat::Jpeg,
-        "image/png" => ImageFormat::Png,
-        "image/gif" => ImageFormat::Gif,
-        _ => return Err(Error::from(format!("Unknown source image format: {}", content_type))),
-    };
+/// Sniffs the real image format from the fetched bytes rather than trusting the upstream
+/// `content-type` header, and rejects SVG/XML payloads outright.
+fn detect_image_format(bytes: &[u8]) -> std::result::Result<ImageFormat, AppError> {
+    if looks_like_svg(bytes) {
+        return Err(AppError::UnsupportedFormat(
+            "SVG source images are not supported".into(),
+        ));
+    }
+
+    image::guess_format(bytes).map_err(|_| {
+        AppError::UnsupportedFormat("Could not determine source image format".into())
+    })
+}
 
-    Ok(format)
+fn looks_like_svg(bytes: &[u8]) -> bool {
+    let head_len = bytes.len().min(512);
+    let head = String::from_utf8_lossy(&bytes[..head_len]).to_ascii_lowercase();
+    let head = head.trim_start();
+    head.starts_with("<?xml") || head.starts_with("<svg") || head.contains("<svg")
 }
 
-fn generate_icon(icon: &Icon, source: &DynamicImage) -> DynamicImage {
-    source.resize(
-        icon.width,
-        icon.height,
-        image::imageops::FilterType::Triangle,
-    )
+fn generate_icon(
+    icon: &Icon,
+    source: &DynamicImage,
+    fit_mode: FitMode,
+    background: image::Rgba<u8>,
+) -> DynamicImage {
+    fit::apply(fit_mode, source, icon.width, icon.height, background)
 }
 
-fn make_response(icon_img: &DynamicImage) -> Result<Response> {
+fn make_response(
+    icon_img: &DynamicImage,
+    format: ImageKind,
+) -> std::result::Result<Response, AppError> {
     let mut buf: Vec<u8> = Vec::new();
+    let output_format = match format {
+        ImageKind::Png => ImageOutputFormat::Png,
+        ImageKind::WebP => ImageOutputFormat::WebP,
+        ImageKind::Avif => ImageOutputFormat::Avif,
+    };
     icon_img
-        .write_to(&mut Cursor::new(&mut buf), ImageOutputFormat::Png)
-        .map_err(|e| Error::from(e.to_string()))?;
-
-    let mut response = Response::from_bytes(buf)?;
-    response.headers_mut().set("content-type", "image/png")?;
+        .write_to(&mut Cursor::new(&mut buf), output_format)
+        .map_err(|e| AppError::Encode(e.to_string()))?;
+
+    let mut response =
+        Response::from_bytes(buf).map_err(|e| AppError::Encode(e.to_string()))?;
+    response
+        .headers_mut()
+        .set("content-type", format.content_type())
+        .map_err(|e| AppError::Encode(e.to_string()))?;
     Ok(response)
 }
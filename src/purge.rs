@@ -0,0 +1,181 @@
+use futures::future::join_all;
+use worker::{Cache, Result, Url};
+
+use crate::fit::FitMode;
+use crate::format::{self, ImageKind};
+
+/// Apple touch icon sizes requested in the wild, per Apple's HIG and common device lineups.
+/// `None` stands for the unsized path (`apple-touch-icon.png`), which defaults to 60x60.
+const SIZES: &[Option<u32>] = &[
+    None,
+    Some(57),
+    Some(60),
+    Some(72),
+    Some(76),
+    Some(114),
+    Some(120),
+    Some(144),
+    Some(152),
+    Some(167),
+    Some(180),
+    Some(192),
+    Some(1024),
+];
+const PRECOMPOSED: &[bool] = &[false, true];
+const FIT_QUERIES: &[Option<FitMode>] = &[
+    None,
+    Some(FitMode::Cover),
+    Some(FitMode::Contain),
+    Some(FitMode::Stretch),
+];
+const FORMAT_QUERIES: &[Option<ImageKind>] = &[
+    None,
+    Some(ImageKind::Png),
+    Some(ImageKind::WebP),
+    Some(ImageKind::Avif),
+];
+const ALL_FORMATS: &[ImageKind] = &[ImageKind::Png, ImageKind::WebP, ImageKind::Avif];
+
+/// Evicts every negotiated format/size/mode variant of the apple touch icon from the edge
+/// cache, so a refreshed `SOURCE_IMAGE_URL` is reflected immediately instead of waiting out
+/// the existing `cache-control` TTL. Reconstructs cache keys with the same `format::cache_key`
+/// helper request handling uses, across every path and query shape a real client can produce,
+/// including the bare, no-query requests ordinary devices actually send, and runs the resulting
+/// deletes concurrently rather than one at a time. Deliberately does not enumerate `?bg=`: it's
+/// an arbitrary hex color with no fixed set of values, so a custom `Contain` background a client
+/// requested explicitly survives until its `cache-control` TTL expires. Returns the number of
+/// cache entries actually evicted.
+pub async fn purge_all(origin: &Url) -> Result<u32> {
+    let cache = Cache::default();
+    let mut keys = Vec::new();
+
+    for &size in SIZES {
+        for &precomposed in PRECOMPOSED {
+            let path = icon_path(size, precomposed);
+            for &fit_query in FIT_QUERIES {
+                for &format_query in FORMAT_QUERIES {
+                    let url = build_url(origin, &path, fit_query, format_query);
+                    match format_query {
+                        // An explicit `?format=` query pins the resolved format, so only that
+                        // one key can exist.
+                        Some(format) => keys.push(format::cache_key(&url, format)),
+                        // With no `?format=` query, the resolved format depends on the
+                        // client's `Accept` header (or the worker-wide default) at request
+                        // time, so try every format the key could have been stored under.
+                        None => {
+                            for &format in ALL_FORMATS {
+                                keys.push(format::cache_key(&url, format));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let deletions = keys.iter().map(|key| cache.delete(key, true));
+    let purged = join_all(deletions)
+        .await
+        .into_iter()
+        .collect::<Result<Vec<bool>>>()?
+        .into_iter()
+        .filter(|&deleted| deleted)
+        .count() as u32;
+
+    Ok(purged)
+}
+
+fn icon_path(size: Option<u32>, precomposed: bool) -> String {
+    let mut path = match size {
+        Some(size) => format!("apple-touch-icon-{0}x{0}", size),
+        None => "apple-touch-icon".to_string(),
+    };
+    if precomposed {
+        path.push_str("-precomposed");
+    }
+    path.push_str(".png");
+    path
+}
+
+fn build_url(
+    origin: &Url,
+    path: &str,
+    fit_query: Option<FitMode>,
+    format_query: Option<ImageKind>,
+) -> Url {
+    let mut url = origin.clone();
+    url.set_path(path);
+
+    let mut pairs = Vec::new();
+    if let Some(fit_mode) = fit_query {
+        pairs.push(format!("fit={}", fit_mode.token()));
+    }
+    if let Some(format) = format_query {
+        pairs.push(format!("format={}", format.token()));
+    }
+
+    url.set_query(if pairs.is_empty() {
+        None
+    } else {
+        Some(&pairs.join("&"))
+    });
+
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn icon_path_covers_unsized_sized_and_precomposed_forms() {
+        assert_eq!(icon_path(None, false), "apple-touch-icon.png");
+        assert_eq!(icon_path(None, true), "apple-touch-icon-precomposed.png");
+        assert_eq!(icon_path(Some(180), false), "apple-touch-icon-180x180.png");
+        assert_eq!(
+            icon_path(Some(180), true),
+            "apple-touch-icon-180x180-precomposed.png"
+        );
+    }
+
+    #[test]
+    fn build_url_with_no_hints_has_no_query() {
+        let origin: Url = "https://example.com/whatever".parse().unwrap();
+        let url = build_url(&origin, "apple-touch-icon.png", None, None);
+        assert_eq!(url.as_str(), "https://example.com/apple-touch-icon.png");
+    }
+
+    #[test]
+    fn build_url_includes_requested_fit_and_format() {
+        let origin: Url = "https://example.com/whatever".parse().unwrap();
+        let url = build_url(
+            &origin,
+            "apple-touch-icon-120x120.png",
+            Some(FitMode::Contain),
+            Some(ImageKind::Avif),
+        );
+        assert_eq!(
+            url.as_str(),
+            "https://example.com/apple-touch-icon-120x120.png?fit=contain&format=avif"
+        );
+    }
+
+    #[test]
+    fn build_url_reconstructed_key_matches_request_key_regardless_of_query_order() {
+        let origin: Url = "https://example.com/whatever".parse().unwrap();
+        let purge_url = build_url(
+            &origin,
+            "apple-touch-icon.png",
+            Some(FitMode::Cover),
+            Some(ImageKind::WebP),
+        );
+        let request_url: Url = "https://example.com/apple-touch-icon.png?format=webp&fit=cover"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            format::cache_key(&purge_url, ImageKind::WebP),
+            format::cache_key(&request_url, ImageKind::WebP)
+        );
+    }
+}